@@ -1,14 +1,17 @@
-use chrono::Local;
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, Timelike};
 use clap::Parser;
 use decimal_percentage::Percentage;
 use measurements::{Current, Voltage};
 use rppal::gpio::{Gpio, InputPin, Level, OutputPin};
 use rppal::i2c::I2c;
 use simple_signal::{self, Signal};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex as StdMutex};
 use thiserror::Error;
 use tokio::process::Command;
 use tokio::select;
 use tokio::sync::Mutex;
+use tokio::task;
 use tokio::time::{sleep, Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
@@ -17,10 +20,25 @@ const I2C_IP5310_VOLTAGE_COMMAND: u8 = 0x02;
 const I2C_IP5310_CAPACITY_COMMAND: u8 = 0x04;
 const I2C_IP5310_CURRENT_COMMAND: u8 = 0x14;
 
+const I2C_DS1307_ADDR: u16 = 0x68;
+const I2C_DS1307_SECONDS_REGISTER: u8 = 0x00;
+const I2C_DS1307_MINUTES_REGISTER: u8 = 0x01;
+const I2C_DS1307_HOURS_REGISTER: u8 = 0x02;
+const I2C_DS1307_DAY_OF_WEEK_REGISTER: u8 = 0x03;
+const I2C_DS1307_DATE_REGISTER: u8 = 0x04;
+const I2C_DS1307_MONTH_REGISTER: u8 = 0x05;
+const I2C_DS1307_YEAR_REGISTER: u8 = 0x06;
+const DS1307_CLOCK_HALT_BIT: u8 = 0x80;
+const DS1307_HOUR_12_24_BIT: u8 = 0x40;
+
 const GPIO_BUTTON: u8 = 5;
 const GPIO_POWER_LOSS: u8 = 6;
 const GPIO_SOFTWARE_ALIVE: u8 = 12;
 const GPIO_BUZZER: u8 = 20;
+const GPIO_DC_OUT: u8 = 26;
+
+const DC_OUT_CUT_DELAY: Duration = Duration::from_secs(10);
+const DC_OUT_PULSE_DURATION: Duration = Duration::from_secs(3);
 
 /// USV X728 control software
 #[derive(Parser, Debug)]
@@ -37,19 +55,72 @@ struct Args {
     /// Timeout in seconds after power loss to shut down system
     #[arg(long)]
     timeout: u64,
+
+    /// Battery pack capacity in mAh, used to estimate remaining runtime from discharge current
+    #[arg(long)]
+    battery_capacity: u32,
+
+    /// Number of times to retry a failed I2C read before surfacing the error
+    #[arg(long)]
+    i2c_retries: u8,
+
+    /// Fixed backoff between I2C read retries, in milliseconds
+    #[arg(long)]
+    i2c_retry_backoff_ms: u64,
+
+    /// Safety margin in seconds; shut down once the estimated remaining runtime drops below this
+    #[arg(long)]
+    runtime_margin: u64,
+
+    /// Interval in milliseconds to toggle the software-alive pin as a watchdog heartbeat.
+    /// Ceasing the heartbeat is the intended "I'm going down" signal during shutdown.
+    #[arg(long)]
+    heartbeat_interval: u64,
+
+    /// Synchronize the system clock from the onboard DS1307 RTC on startup
+    /// and write it back to the RTC on shutdown
+    #[arg(long)]
+    sync_rtc: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     let cancellation_token = setup_signals();
-    let usv = Box::new(X728USV::new()?);
+    let usv = Box::new(X728USV::new(
+        args.i2c_retries,
+        Duration::from_millis(args.i2c_retry_backoff_ms),
+    )?);
+    let rtc = if args.sync_rtc {
+        Some(Rtc::new()?)
+    } else {
+        None
+    };
+
+    if let Some(rtc) = &rtc {
+        sync_system_clock_from_rtc(rtc).await?;
+    }
 
-    let power_loss_routine = power_loss_routine(&usv, &args, cancellation_token.clone());
-    let button_routine = button_routine(&usv, &args, cancellation_token.clone());
+    let power_loss_routine =
+        power_loss_routine(&usv, &args, rtc.as_ref(), cancellation_token.clone());
+    let button_routine = button_routine(&usv, &args, rtc.as_ref(), cancellation_token.clone());
+    let heartbeat_routine = heartbeat_routine(&usv, &args, cancellation_token.clone());
 
-    power_loss_routine.await?;
-    button_routine.await?;
+    tokio::try_join!(power_loss_routine, button_routine, heartbeat_routine)?;
+
+    Ok(())
+}
+
+async fn heartbeat_routine(
+    usv: &Box<X728USV>,
+    args: &Args,
+    cancellation_token: CancellationToken,
+) -> Result<(), Box<dyn std::error::Error>> {
+    usv.run_heartbeat(
+        Duration::from_millis(args.heartbeat_interval),
+        cancellation_token,
+    )
+    .await;
 
     Ok(())
 }
@@ -57,17 +128,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 async fn power_loss_routine(
     usv: &Box<X728USV>,
     args: &Args,
+    rtc: Option<&Rtc>,
     cancellation_token: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(power_loss_action) = usv
         .get_power_loss_action(
             Duration::from_secs(args.timeout),
+            f64::from(args.battery_capacity),
+            Duration::from_secs(args.runtime_margin),
             cancellation_token.clone(),
         )
         .await
     {
-        cancellation_token.cancel();
-
         match power_loss_action {
             PowerLossAction::CapacityLow(capacity) => {
                 println!(
@@ -81,9 +153,15 @@ async fn power_loss_routine(
                     elapsed.as_secs()
                 );
             }
+            PowerLossAction::RuntimeLow(time_to_empty) => {
+                println!(
+                    "Estimated runtime of {} seconds left, below safety margin! Shutting down...",
+                    time_to_empty.as_secs()
+                );
+            }
         }
 
-        run_shell_command(args.shutdown.clone()).await?;
+        shutdown_system(usv, args, rtc, &cancellation_token).await?;
     }
 
     Ok(())
@@ -92,11 +170,10 @@ async fn power_loss_routine(
 async fn button_routine(
     usv: &Box<X728USV>,
     args: &Args,
+    rtc: Option<&Rtc>,
     cancellation_token: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(button_action) = usv.get_button_action(cancellation_token.clone()).await {
-        cancellation_token.cancel();
-
         match button_action {
             ButtonAction::Reboot(elapsed) => {
                 println!(
@@ -104,6 +181,8 @@ async fn button_routine(
                     elapsed.as_millis()
                 );
 
+                cancellation_token.cancel();
+
                 run_shell_command(args.reboot.clone()).await?;
             }
             ButtonAction::Shutdown(elapsed) => {
@@ -112,7 +191,10 @@ async fn button_routine(
                     elapsed.as_millis()
                 );
 
-                run_shell_command(args.shutdown.clone()).await?;
+                usv.beep(&Ringtone::imminent_shutdown(), cancellation_token.clone())
+                    .await;
+
+                shutdown_system(usv, args, rtc, &cancellation_token).await?;
             }
         }
     }
@@ -120,6 +202,63 @@ async fn button_routine(
     Ok(())
 }
 
+/// Closes the switched DC output and tears the system down. `power_loss_routine`
+/// and `button_routine` can both reach this on the same poll cycle now that they
+/// run concurrently under `tokio::try_join!`; `DcOutController::close` only returns
+/// `true` for whichever caller actually wins the race, so the shutdown command and
+/// RTC write never run twice.
+async fn shutdown_system(
+    usv: &Box<X728USV>,
+    args: &Args,
+    rtc: Option<&Rtc>,
+    cancellation_token: &CancellationToken,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !usv.dc_out.close().await {
+        println!(
+            "Shutdown already in progress (dc output on: {}, off: {}), ignoring duplicate request.",
+            usv.dc_out.is_on().await,
+            usv.dc_out.is_off().await
+        );
+
+        return Ok(());
+    }
+
+    cancellation_token.cancel();
+
+    if let Some(rtc) = rtc {
+        persist_system_clock_to_rtc(rtc)?;
+    }
+
+    run_shell_command(args.shutdown.clone()).await?;
+
+    // Hold the process open until the physical DC cutoff pulse actually
+    // fires; otherwise `main` returns as soon as the other routines notice
+    // the cancellation, and tokio drops the still-sleeping cutoff task.
+    usv.dc_out.wait_until_off().await;
+
+    Ok(())
+}
+
+async fn sync_system_clock_from_rtc(rtc: &Rtc) -> Result<(), Box<dyn std::error::Error>> {
+    let time = rtc.read_time()?;
+
+    println!("Synchronizing system clock from RTC: {}", time);
+
+    run_shell_command(format!("date -u -s @{}", time.and_utc().timestamp())).await?;
+
+    Ok(())
+}
+
+fn persist_system_clock_to_rtc(rtc: &Rtc) -> Result<(), Box<dyn std::error::Error>> {
+    let now = chrono::Utc::now().naive_utc();
+
+    rtc.set_time(now)?;
+
+    println!("Wrote system clock to RTC: {}", now);
+
+    Ok(())
+}
+
 async fn run_shell_command(command: String) -> Result<(), Box<dyn std::error::Error>> {
     let mut parts = command.split_whitespace();
 
@@ -162,6 +301,30 @@ pub enum CommandError {
     CommandFailed { code: i32 },
 }
 
+/// Classifies a failed SMBus transaction so callers can log meaningfully.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum I2cReadError {
+    #[error("Device did not acknowledge the I2C transaction (missing or busy).")]
+    NoAcknowledge,
+
+    #[error("I2C bus error: {0}")]
+    Bus(rppal::i2c::Error),
+}
+
+impl From<rppal::i2c::Error> for I2cReadError {
+    fn from(err: rppal::i2c::Error) -> I2cReadError {
+        let is_no_acknowledge =
+            matches!(&err, rppal::i2c::Error::Io(io_err) if io_err.raw_os_error() == Some(121));
+
+        if is_no_acknowledge {
+            I2cReadError::NoAcknowledge
+        } else {
+            I2cReadError::Bus(err)
+        }
+    }
+}
+
 #[derive(Debug)]
 struct X728USV {
     gpio: Gpio,
@@ -169,7 +332,12 @@ struct X728USV {
     gpio_button: InputPin,
     gpio_power_loss: InputPin,
     gpio_buzzer: Mutex<OutputPin>,
-    gpio_software_alive: OutputPin,
+    gpio_software_alive: Mutex<OutputPin>,
+    dc_out: DcOutController,
+    i2c_retries: u8,
+    i2c_retry_backoff: Duration,
+    voltage_filter: SmoothingFilter,
+    capacity_filter: SmoothingFilter,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -194,10 +362,212 @@ enum ButtonAction {
 enum PowerLossAction {
     CapacityLow(Percentage),
     Timeout(Duration),
+    RuntimeLow(Duration),
+}
+
+const SMOOTHING_WINDOW: usize = 5;
+
+/// Median filter over a fixed-size window of samples, used to stop a single
+/// outlier reading from tripping the critical-capacity shutdown branch.
+#[derive(Debug)]
+struct SmoothingFilter {
+    samples: StdMutex<VecDeque<f64>>,
+}
+
+impl SmoothingFilter {
+    fn new() -> SmoothingFilter {
+        SmoothingFilter {
+            samples: StdMutex::new(VecDeque::with_capacity(SMOOTHING_WINDOW)),
+        }
+    }
+
+    fn sample(&self, value: f64) -> f64 {
+        let mut samples = self
+            .samples
+            .lock()
+            .expect("smoothing filter mutex poisoned");
+
+        if samples.len() == SMOOTHING_WINDOW {
+            samples.pop_front();
+        }
+
+        samples.push_back(value);
+
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).expect("reading is never NaN"));
+
+        sorted[sorted.len() / 2]
+    }
+}
+
+/// Integrates discharge current over time to estimate remaining battery runtime.
+#[derive(Debug)]
+struct RuntimeEstimator {
+    pack_capacity_mah: f64,
+    remaining_mah: f64,
+    last_sample: Option<Instant>,
+}
+
+impl RuntimeEstimator {
+    fn new(pack_capacity_mah: f64) -> RuntimeEstimator {
+        RuntimeEstimator {
+            pack_capacity_mah,
+            remaining_mah: pack_capacity_mah,
+            last_sample: None,
+        }
+    }
+
+    /// Called when the power source switches back to mains, so the next
+    /// discharge cycle starts from a full pack again.
+    fn reset(&mut self) {
+        self.remaining_mah = self.pack_capacity_mah;
+        self.last_sample = None;
+    }
+
+    /// Subtracts the charge drawn since the previous sample, then clamps the
+    /// result to the capacity reported by the gauge to correct for drift.
+    fn sample(&mut self, current: Current, capacity: Percentage) {
+        let now = Instant::now();
+
+        if let Some(last_sample) = self.last_sample {
+            let elapsed_hours = now.duration_since(last_sample).as_secs_f64() / 3600.0;
+            let discharge_ma = current.as_milliamperes().max(0.0);
+
+            self.remaining_mah -= discharge_ma * elapsed_hours;
+        }
+
+        let gauge_mah = self.pack_capacity_mah * capacity;
+
+        if gauge_mah < self.remaining_mah {
+            self.remaining_mah = gauge_mah;
+        }
+
+        self.last_sample = Some(now);
+    }
+
+    /// Projected time until the pack is empty at the given discharge current.
+    fn time_to_empty(&self, current: Current) -> Duration {
+        const EPSILON_MA: f64 = 1.0;
+
+        let discharge_ma = current.as_milliamperes().max(EPSILON_MA);
+        let hours = (self.remaining_mah / discharge_ma).max(0.0);
+
+        Duration::from_secs_f64(hours * 3600.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DcOutStatus {
+    On,
+    Off,
+    TurningOff,
+}
+
+/// Drives the board's switched DC output through an explicit state machine,
+/// instead of raw pin writes scattered through callers. `close` is idempotent
+/// and the transition to `Off` runs as a cancellation-aware background task
+/// so callers don't block on the "wait, set high, wait, set low" power-cut
+/// sequence.
+#[derive(Debug, Clone)]
+struct DcOutController {
+    gpio_dc_out: Arc<Mutex<OutputPin>>,
+    status: Arc<Mutex<DcOutStatus>>,
+    // Guards the cutoff sequence below. Deliberately independent of the
+    // program-wide shutdown token: callers cancel that token right after
+    // `close()` returns, which would otherwise collapse the 10s+3s delay.
+    cancellation_token: CancellationToken,
+}
+
+impl DcOutController {
+    fn new(gpio: &Gpio) -> Result<DcOutController, Box<dyn std::error::Error>> {
+        let gpio_dc_out = gpio.get(GPIO_DC_OUT)?.into_output_low();
+
+        Ok(DcOutController {
+            gpio_dc_out: Arc::new(Mutex::new(gpio_dc_out)),
+            status: Arc::new(Mutex::new(DcOutStatus::On)),
+            cancellation_token: CancellationToken::new(),
+        })
+    }
+
+    async fn status(&self) -> DcOutStatus {
+        *self.status.lock().await
+    }
+
+    async fn is_on(&self) -> bool {
+        self.status().await == DcOutStatus::On
+    }
+
+    async fn is_off(&self) -> bool {
+        self.status().await == DcOutStatus::Off
+    }
+
+    /// Blocks until the cutoff sequence started by `close()` reaches `Off`.
+    /// `close()` only spawns that sequence, so callers that need the
+    /// process to stay alive for the physical power-cut pulse (rather than
+    /// exiting the instant the cancellation token is observed elsewhere)
+    /// must await this before returning.
+    async fn wait_until_off(&self) {
+        while !self.is_off().await {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Atomically transitions `On` -> `TurningOff`, returning whether this
+    /// call actually won the transition. Split out from `close()` so the
+    /// idempotency guard can be unit tested without the GPIO handle.
+    async fn try_begin_close(status: &Mutex<DcOutStatus>) -> bool {
+        let mut status = status.lock().await;
+
+        if *status != DcOutStatus::On {
+            return false;
+        }
+
+        *status = DcOutStatus::TurningOff;
+
+        true
+    }
+
+    /// Spawns the "wait, then pulse" power-cut sequence and returns
+    /// immediately; callers that need to block until it finishes should
+    /// await `wait_until_off()`. Returns `false` without touching the GPIO
+    /// if a cutoff is already in flight or has already completed, so
+    /// callers can avoid acting twice.
+    async fn close(&self) -> bool {
+        if !Self::try_begin_close(&self.status).await {
+            return false;
+        }
+
+        let gpio_dc_out = self.gpio_dc_out.clone();
+        let status = self.status.clone();
+        let cancellation_token = self.cancellation_token.clone();
+
+        task::spawn(async move {
+            select! {
+                _ = cancellation_token.cancelled() => {}
+                _ = sleep(DC_OUT_CUT_DELAY) => {}
+            }
+
+            gpio_dc_out.lock().await.set_high();
+
+            select! {
+                _ = cancellation_token.cancelled() => {}
+                _ = sleep(DC_OUT_PULSE_DURATION) => {}
+            }
+
+            gpio_dc_out.lock().await.set_low();
+
+            *status.lock().await = DcOutStatus::Off;
+        });
+
+        true
+    }
 }
 
 impl X728USV {
-    fn new() -> Result<X728USV, Box<dyn std::error::Error>> {
+    fn new(
+        i2c_retries: u8,
+        i2c_retry_backoff: Duration,
+    ) -> Result<X728USV, Box<dyn std::error::Error>> {
         let gpio = Gpio::new()?;
         let mut i2c = I2c::new()?;
 
@@ -205,6 +575,7 @@ impl X728USV {
         let gpio_power_loss = gpio.get(GPIO_POWER_LOSS)?.into_input();
         let gpio_software_alive = gpio.get(GPIO_SOFTWARE_ALIVE)?.into_output_high();
         let gpio_button = gpio.get(GPIO_BUTTON)?.into_input();
+        let dc_out = DcOutController::new(&gpio)?;
 
         i2c.set_slave_address(I2C_IP5310_ADDR)?;
 
@@ -214,7 +585,12 @@ impl X728USV {
             gpio_button,
             gpio_power_loss,
             gpio_buzzer: Mutex::new(gpio_buzzer),
-            gpio_software_alive,
+            gpio_software_alive: Mutex::new(gpio_software_alive),
+            dc_out,
+            i2c_retries,
+            i2c_retry_backoff,
+            voltage_filter: SmoothingFilter::new(),
+            capacity_filter: SmoothingFilter::new(),
         })
     }
 
@@ -232,37 +608,78 @@ impl X728USV {
         }
     }
 
-    fn get_voltage(&self) -> rppal::i2c::Result<Voltage> {
-        let read = u16::from_be(self.i2c.smbus_read_word(I2C_IP5310_VOLTAGE_COMMAND)?);
+    async fn get_voltage(&self) -> Result<Voltage, I2cReadError> {
+        let read = u16::from_be(
+            self.smbus_read_word_with_retry(I2C_IP5310_VOLTAGE_COMMAND)
+                .await?,
+        );
 
         let milli_volts = f64::from(read) * 1.25 / 16.0;
+        let smoothed_milli_volts = self.voltage_filter.sample(milli_volts);
 
-        Ok(Voltage::from_millivolts(milli_volts))
+        Ok(Voltage::from_millivolts(smoothed_milli_volts))
     }
 
-    fn get_current(&self) -> rppal::i2c::Result<Current> {
-        let read = i16::from_be(self.i2c.smbus_read_word(I2C_IP5310_CURRENT_COMMAND)? as i16);
+    async fn get_current(&self) -> Result<Current, I2cReadError> {
+        let read = i16::from_be(
+            self.smbus_read_word_with_retry(I2C_IP5310_CURRENT_COMMAND)
+                .await? as i16,
+        );
 
         let milli_amperes = f64::from(read);
 
         Ok(Current::from_milliamperes(milli_amperes))
     }
 
-    fn get_capacity(&self) -> rppal::i2c::Result<Percentage> {
-        let read = u16::from_be(self.i2c.smbus_read_word(I2C_IP5310_CAPACITY_COMMAND)?);
+    async fn get_capacity(&self) -> Result<Percentage, I2cReadError> {
+        let read = u16::from_be(
+            self.smbus_read_word_with_retry(I2C_IP5310_CAPACITY_COMMAND)
+                .await?,
+        );
 
         let ratio = f64::from(read) / 25600.0f64;
+        let smoothed_ratio = self.capacity_filter.sample(ratio);
 
-        Ok(Percentage::from(ratio))
+        Ok(Percentage::from(smoothed_ratio))
+    }
+
+    /// Retries a failed SMBus word read a fixed number of times with a fixed
+    /// backoff before surfacing the classified error to the caller. The
+    /// backoff sleeps on the tokio timer rather than blocking the worker
+    /// thread, since this is driven from `get_power_loss_action`'s poll loop
+    /// alongside the heartbeat and cancellation checks.
+    async fn smbus_read_word_with_retry(&self, command: u8) -> Result<u16, I2cReadError> {
+        let mut attempt = 0;
+
+        loop {
+            match self.i2c.smbus_read_word(command) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let classified_err = I2cReadError::from(err);
+
+                    if attempt >= self.i2c_retries {
+                        return Err(classified_err);
+                    }
+
+                    println!("I2C read failed ({}), retrying...", classified_err);
+
+                    attempt += 1;
+                    sleep(self.i2c_retry_backoff).await;
+                }
+            }
+        }
     }
 
     async fn get_power_loss_action(
         &self,
         shutdown_duration: Duration,
+        pack_capacity_mah: f64,
+        runtime_safety_margin: Duration,
         cancellation_token: CancellationToken,
     ) -> Option<PowerLossAction> {
         let mut last_source = self.get_power_source();
         let mut state_changed = Instant::now();
+        let mut runtime_estimator = RuntimeEstimator::new(pack_capacity_mah);
 
         while !cancellation_token.is_cancelled() {
             let new_source = self.get_power_source();
@@ -278,13 +695,10 @@ impl X728USV {
                             Local::now().format("%d-%m-%Y %H:%M:%S")
                         );
 
-                        self.beep(
-                            Duration::from_millis(50),
-                            Duration::from_millis(100),
-                            2,
-                            cancellation_token.clone(),
-                        )
-                        .await;
+                        runtime_estimator.reset();
+
+                        self.beep(&Ringtone::power_restored(), cancellation_token.clone())
+                            .await;
                     }
                     PowerSource::Battery => {
                         println!(
@@ -292,13 +706,8 @@ impl X728USV {
                             Local::now().format("%d-%m-%Y %H:%M:%S")
                         );
 
-                        self.beep(
-                            Duration::from_millis(500),
-                            Duration::from_millis(500),
-                            3,
-                            cancellation_token.clone(),
-                        )
-                        .await;
+                        self.beep(&Ringtone::power_lost(), cancellation_token.clone())
+                            .await;
                     }
                 }
             }
@@ -307,16 +716,33 @@ impl X728USV {
                 let elapsed = state_changed.elapsed();
 
                 if elapsed > shutdown_duration {
+                    self.beep(&Ringtone::imminent_shutdown(), cancellation_token.clone())
+                        .await;
+
                     return Some(PowerLossAction::Timeout(elapsed));
                 }
 
-                match self.get_capacity() {
-                    Ok(new_capacity) => {
+                match tokio::join!(self.get_capacity(), self.get_current()) {
+                    (Ok(new_capacity), Ok(new_current)) => {
                         if new_capacity < Percentage::from(0.2f32) {
+                            self.beep(&Ringtone::critical_capacity(), cancellation_token.clone())
+                                .await;
+
                             return Some(PowerLossAction::CapacityLow(new_capacity));
                         }
+
+                        runtime_estimator.sample(new_current, new_capacity);
+                        let time_to_empty = runtime_estimator.time_to_empty(new_current);
+
+                        if time_to_empty < runtime_safety_margin {
+                            self.beep(&Ringtone::imminent_shutdown(), cancellation_token.clone())
+                                .await;
+
+                            return Some(PowerLossAction::RuntimeLow(time_to_empty));
+                        }
                     }
-                    Err(err) => println!("Error while reading capacity: {}", err),
+                    (Err(err), _) => println!("Error while reading capacity: {}", err),
+                    (_, Err(err)) => println!("Error while reading current: {}", err),
                 }
             }
 
@@ -344,13 +770,8 @@ impl X728USV {
                     }
                 }
                 ButtonState::Pressed => {
-                    self.beep(
-                        Duration::from_millis(200),
-                        Duration::from_millis(200),
-                        1,
-                        cancellation_token.clone(),
-                    )
-                    .await;
+                    self.beep(&Ringtone::button_ack(), cancellation_token.clone())
+                        .await;
 
                     let pulse_start = Instant::now();
 
@@ -379,16 +800,11 @@ impl X728USV {
         None
     }
 
-    async fn beep(
-        &self,
-        high_duration: Duration,
-        low_duration: Duration,
-        count: u8,
-        cancellation_token: CancellationToken,
-    ) {
+    async fn beep(&self, ringtone: &Ringtone, cancellation_token: CancellationToken) {
         let mut gpio_buzzer = self.gpio_buzzer.lock().await;
+        let steps = ringtone.expand_steps();
 
-        for counter in 0..count {
+        for (index, (on_duration, off_duration)) in steps.iter().enumerate() {
             if cancellation_token.is_cancelled() {
                 return;
             }
@@ -397,19 +813,184 @@ impl X728USV {
 
             select! {
                 _ = cancellation_token.cancelled() => {}
-                _ = sleep(high_duration) => {}
+                _ = sleep(*on_duration) => {}
             }
 
             gpio_buzzer.set_low();
 
-            if (counter + 1u8) < count {
+            if index + 1 < steps.len() {
                 select! {
                     _ = cancellation_token.cancelled() => {}
-                    _ = sleep(low_duration) => {}
+                    _ = sleep(*off_duration) => {}
                 }
             }
         }
     }
+
+    /// Toggles the software-alive pin at a fixed interval to prove liveness to
+    /// the board's watchdog. Stops as soon as the token is cancelled, which is
+    /// the board's cue that the control process is going down.
+    ///
+    /// Unlike `DcOutController`'s cutoff guard or `Ringtone`'s step
+    /// expansion, every line of this loop either touches the real GPIO pin
+    /// or is the single `is_cancelled()` check itself, so there's no pure
+    /// piece left to pull out into a unit test without a mock GPIO backend,
+    /// which this crate doesn't have.
+    async fn run_heartbeat(&self, interval: Duration, cancellation_token: CancellationToken) {
+        while !cancellation_token.is_cancelled() {
+            self.gpio_software_alive.lock().await.toggle();
+
+            select! {
+                _ = cancellation_token.cancelled() => {}
+                _ = sleep(interval) => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Ringtone {
+    steps: Vec<(Duration, Duration)>,
+    repeat: u8,
+}
+
+impl Ringtone {
+    fn new(steps: Vec<(Duration, Duration)>, repeat: u8) -> Ringtone {
+        Ringtone { steps, repeat }
+    }
+
+    /// Repeats `steps` end-to-end `repeat` times into the flat sequence
+    /// `beep()` plays through.
+    fn expand_steps(&self) -> Vec<(Duration, Duration)> {
+        self.steps
+            .iter()
+            .cloned()
+            .cycle()
+            .take(self.steps.len() * usize::from(self.repeat))
+            .collect()
+    }
+
+    /// Two short beeps: power supply restored.
+    fn power_restored() -> Ringtone {
+        Ringtone::new(
+            vec![(Duration::from_millis(50), Duration::from_millis(100))],
+            2,
+        )
+    }
+
+    /// Three long beeps: power supply failed, running on battery.
+    fn power_lost() -> Ringtone {
+        Ringtone::new(
+            vec![(Duration::from_millis(500), Duration::from_millis(500))],
+            3,
+        )
+    }
+
+    /// Single beep acknowledging a button press.
+    fn button_ack() -> Ringtone {
+        Ringtone::new(
+            vec![(Duration::from_millis(200), Duration::from_millis(200))],
+            1,
+        )
+    }
+
+    /// Five quick beeps: battery capacity has dropped below the critical threshold.
+    fn critical_capacity() -> Ringtone {
+        Ringtone::new(
+            vec![(Duration::from_millis(100), Duration::from_millis(100))],
+            5,
+        )
+    }
+
+    /// One long beep followed by two short ones, played right before the
+    /// shutdown command is executed.
+    fn imminent_shutdown() -> Ringtone {
+        Ringtone::new(
+            vec![
+                (Duration::from_millis(1000), Duration::from_millis(200)),
+                (Duration::from_millis(200), Duration::from_millis(200)),
+                (Duration::from_millis(200), Duration::from_millis(200)),
+            ],
+            1,
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Rtc {
+    i2c: I2c,
+}
+
+impl Rtc {
+    fn new() -> Result<Rtc, Box<dyn std::error::Error>> {
+        let mut i2c = I2c::new()?;
+
+        i2c.set_slave_address(I2C_DS1307_ADDR)?;
+
+        Ok(Rtc { i2c })
+    }
+
+    fn read_time(&self) -> Result<NaiveDateTime, Box<dyn std::error::Error>> {
+        let seconds = bcd_to_dec(
+            self.i2c.smbus_read_byte(I2C_DS1307_SECONDS_REGISTER)? & !DS1307_CLOCK_HALT_BIT,
+        );
+        let minutes = bcd_to_dec(self.i2c.smbus_read_byte(I2C_DS1307_MINUTES_REGISTER)?);
+        let hours = bcd_to_dec(
+            self.i2c.smbus_read_byte(I2C_DS1307_HOURS_REGISTER)? & !DS1307_HOUR_12_24_BIT,
+        );
+        let date = bcd_to_dec(self.i2c.smbus_read_byte(I2C_DS1307_DATE_REGISTER)?);
+        let month = bcd_to_dec(self.i2c.smbus_read_byte(I2C_DS1307_MONTH_REGISTER)?);
+        let year = 2000
+            + i32::from(bcd_to_dec(
+                self.i2c.smbus_read_byte(I2C_DS1307_YEAR_REGISTER)?,
+            ));
+
+        let date = NaiveDate::from_ymd_opt(year, u32::from(month), u32::from(date))
+            .and_then(|date| {
+                date.and_hms_opt(u32::from(hours), u32::from(minutes), u32::from(seconds))
+            })
+            .ok_or(RtcError::InvalidDateTime)?;
+
+        Ok(date)
+    }
+
+    fn set_time(&self, time: NaiveDateTime) -> rppal::i2c::Result<()> {
+        let year = (time.year() - 2000) as u8;
+
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_SECONDS_REGISTER, dec_to_bcd(time.second() as u8))?;
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_MINUTES_REGISTER, dec_to_bcd(time.minute() as u8))?;
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_HOURS_REGISTER, dec_to_bcd(time.hour() as u8))?;
+        self.i2c.smbus_write_byte(
+            I2C_DS1307_DAY_OF_WEEK_REGISTER,
+            dec_to_bcd(time.weekday().number_from_monday() as u8),
+        )?;
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_DATE_REGISTER, dec_to_bcd(time.day() as u8))?;
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_MONTH_REGISTER, dec_to_bcd(time.month() as u8))?;
+        self.i2c
+            .smbus_write_byte(I2C_DS1307_YEAR_REGISTER, dec_to_bcd(year))?;
+
+        Ok(())
+    }
+}
+
+fn bcd_to_dec(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn dec_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum RtcError {
+    #[error("RTC reported a date or time that cannot be represented.")]
+    InvalidDateTime,
 }
 
 impl std::fmt::Display for ButtonAction {
@@ -435,3 +1016,140 @@ impl std::fmt::Display for ButtonState {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ringtone_expand_steps_repeats_the_pattern_in_full() {
+        let ringtone = Ringtone::new(
+            vec![
+                (Duration::from_millis(100), Duration::from_millis(200)),
+                (Duration::from_millis(300), Duration::from_millis(400)),
+            ],
+            2,
+        );
+
+        assert_eq!(
+            ringtone.expand_steps(),
+            vec![
+                (Duration::from_millis(100), Duration::from_millis(200)),
+                (Duration::from_millis(300), Duration::from_millis(400)),
+                (Duration::from_millis(100), Duration::from_millis(200)),
+                (Duration::from_millis(300), Duration::from_millis(400)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ringtone_expand_steps_single_repeat_is_the_pattern_unchanged() {
+        let ringtone = Ringtone::button_ack();
+
+        assert_eq!(ringtone.expand_steps(), ringtone.steps);
+    }
+
+    #[tokio::test]
+    async fn dc_out_controller_try_begin_close_is_idempotent() {
+        let status = Mutex::new(DcOutStatus::On);
+
+        assert!(DcOutController::try_begin_close(&status).await);
+        assert!(!DcOutController::try_begin_close(&status).await);
+        assert_eq!(*status.lock().await, DcOutStatus::TurningOff);
+    }
+
+    #[tokio::test]
+    async fn dc_out_controller_try_begin_close_refuses_when_already_off() {
+        let status = Mutex::new(DcOutStatus::Off);
+
+        assert!(!DcOutController::try_begin_close(&status).await);
+    }
+
+    #[test]
+    fn smoothing_filter_returns_the_single_sample_before_the_window_fills() {
+        let filter = SmoothingFilter::new();
+
+        assert_eq!(filter.sample(4.0), 4.0);
+    }
+
+    #[test]
+    fn smoothing_filter_rejects_a_single_outlier_via_the_median() {
+        let filter = SmoothingFilter::new();
+
+        filter.sample(4.0);
+        filter.sample(4.1);
+        filter.sample(3.9);
+        filter.sample(4.0);
+
+        assert_eq!(filter.sample(50.0), 4.0);
+    }
+
+    #[test]
+    fn smoothing_filter_drops_samples_older_than_the_window() {
+        let filter = SmoothingFilter::new();
+
+        for _ in 0..SMOOTHING_WINDOW {
+            filter.sample(1.0);
+        }
+
+        assert_eq!(filter.sample(2.0), 1.0);
+
+        // Only the last five samples count: [1, 1, 2, 2, 2] has median 2.0,
+        // whereas keeping every sample would still give 1.0.
+        filter.sample(2.0);
+        assert_eq!(filter.sample(2.0), 2.0);
+    }
+
+    #[test]
+    fn runtime_estimator_time_to_empty_scales_with_capacity_and_current() {
+        let estimator = RuntimeEstimator::new(6000.0);
+
+        assert_eq!(
+            estimator.time_to_empty(Current::from_milliamperes(2000.0)),
+            Duration::from_secs_f64(3.0 * 3600.0)
+        );
+    }
+
+    #[test]
+    fn runtime_estimator_sample_clamps_to_a_lower_gauge_reading() {
+        let mut estimator = RuntimeEstimator::new(6000.0);
+
+        estimator.sample(Current::from_milliamperes(1000.0), Percentage::from(0.5f32));
+
+        assert_eq!(estimator.remaining_mah, 3000.0);
+    }
+
+    #[test]
+    fn runtime_estimator_reset_restores_full_pack_capacity() {
+        let mut estimator = RuntimeEstimator::new(6000.0);
+
+        estimator.sample(Current::from_milliamperes(1000.0), Percentage::from(0.1f32));
+        estimator.reset();
+
+        assert_eq!(estimator.remaining_mah, 6000.0);
+        assert!(estimator.last_sample.is_none());
+    }
+
+    #[test]
+    fn bcd_dec_round_trip_covers_all_register_values() {
+        for value in 0..=99 {
+            assert_eq!(bcd_to_dec(dec_to_bcd(value)), value);
+        }
+    }
+
+    #[test]
+    fn dec_to_bcd_packs_tens_and_units_into_nibbles() {
+        assert_eq!(dec_to_bcd(0), 0x00);
+        assert_eq!(dec_to_bcd(9), 0x09);
+        assert_eq!(dec_to_bcd(42), 0x42);
+        assert_eq!(dec_to_bcd(59), 0x59);
+    }
+
+    #[test]
+    fn bcd_to_dec_unpacks_tens_and_units_from_nibbles() {
+        assert_eq!(bcd_to_dec(0x00), 0);
+        assert_eq!(bcd_to_dec(0x09), 9);
+        assert_eq!(bcd_to_dec(0x42), 42);
+        assert_eq!(bcd_to_dec(0x59), 59);
+    }
+}